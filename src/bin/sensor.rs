@@ -1,14 +1,42 @@
 use chrono::{DateTime, Local};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use csv_async::AsyncSerializer;
 use futures::stream::StreamExt;
 use mavlink::common::MavMessage;
-use metec_sensor::data::{handle_sensor_data, open_serial_port, DroneLocation};
+use mavlink::MavlinkVersion;
+use metec_sensor::data::{
+    handle_sensor_data, open_replay_sensor_file, open_serial_port, DroneLocation, SensorData,
+};
 use metec_sensor::error::*;
-use metec_sensor::mavlink::{heartbeat_stream, Telem};
+use metec_sensor::lora::LoraLink;
+use metec_sensor::mavlink::{heartbeat_stream, MavSigningConfig, Telem, GLOBAL_POSITION_INT_MSG_ID};
 use std::path::PathBuf;
 use std::time::Duration;
 use tokio::fs::File;
+use tokio_serial::SerialPortBuilderExt;
+
+/// Outgoing Mavlink protocol version, mirroring `mavlink::MavlinkVersion` so it can
+/// be parsed from the CLI.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MavlinkVersionArg {
+    V1,
+    V2,
+}
+
+impl From<MavlinkVersionArg> for MavlinkVersion {
+    fn from(value: MavlinkVersionArg) -> Self {
+        match value {
+            MavlinkVersionArg::V1 => MavlinkVersion::V1,
+            MavlinkVersionArg::V2 => MavlinkVersion::V2,
+        }
+    }
+}
+
+impl std::fmt::Display for MavlinkVersionArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.to_possible_value().unwrap().get_name().fmt(f)
+    }
+}
 
 /// Program for reading, storing and transmitting sensor data
 #[derive(Parser, Debug)]
@@ -36,14 +64,11 @@ struct Args {
     #[arg(long, env)]
     sensor_b_baud: u32,
 
-    /// Serial Port for Mavlink
+    /// Mavlink connection string, e.g. `serial:/dev/ttyUSB0:57600`, `udpout:192.168.1.10:14550`,
+    /// `udpin:0.0.0.0:14550` or `tcpout:192.168.1.10:5760`
     #[arg(long, env)]
     mavlink_port: String,
 
-    /// Baud Rate for Mavlink
-    #[arg(long, env)]
-    mavlink_baud: u32,
-
     /// System ID for Mavlink
     #[arg(long, env)]
     mavlink_system_id: u8,
@@ -52,12 +77,58 @@ struct Args {
     #[arg(long, env)]
     mavlink_component_id: u8,
 
+    /// 64 hex character (32 byte) secret key used to sign and verify Mavlink v2 frames.
+    /// Signing is disabled unless this is set.
+    #[arg(long, env)]
+    mavlink_secret_key: Option<String>,
+
+    /// Link ID to sign outgoing Mavlink frames with, only used when `mavlink_secret_key` is set
+    #[arg(long, env, default_value_t = 0)]
+    mavlink_link_id: u8,
+
+    /// Outgoing Mavlink protocol version, for interoperating with autopilots that only speak v1
+    #[arg(long, env, value_enum, default_value_t = MavlinkVersionArg::V2)]
+    mavlink_version: MavlinkVersionArg,
+
+    /// Rate in Hz to actively request GLOBAL_POSITION_INT at via SET_MESSAGE_INTERVAL
+    #[arg(long, env, default_value_t = 2.0)]
+    position_rate_hz: f32,
+
     /// Directory for storing log files
     #[arg(long, env)]
     output_directory: PathBuf,
+
+    /// Replay a recorded Mavlink byte stream from disk instead of opening `mavlink_port` live
+    #[arg(long, env)]
+    replay_mavlink: Option<PathBuf>,
+
+    /// Replay a recorded `*_sensor_a.csv` log from disk instead of opening `sensor_a_port` live
+    #[arg(long, env)]
+    replay_sensor_a: Option<PathBuf>,
+
+    /// Serial Port for the framed LoRa sensor data relay, disabled unless this is set
+    #[arg(long, env)]
+    lora_port: Option<String>,
+
+    /// Baud Rate for the LoRa serial port
+    #[arg(long, env, default_value_t = 57600)]
+    lora_baud: u32,
 }
 
 impl Args {
+    pub fn mavlink_signing(&self) -> Result<Option<MavSigningConfig>> {
+        let Some(secret_key) = &self.mavlink_secret_key else {
+            return Ok(None);
+        };
+
+        let key_bytes = hex::decode(secret_key).map_err(|e| anyhow::anyhow!(e))?;
+        let secret_key: [u8; 32] = key_bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("mavlink_secret_key must be exactly 32 bytes (64 hex characters)"))?;
+
+        Ok(Some(MavSigningConfig::new(self.mavlink_link_id, secret_key)))
+    }
+
     pub fn get_output_file(&self, postfix: &str) -> PathBuf {
         let now: DateTime<Local> = Local::now();
         let time = now.format("%F_%H%M%S");
@@ -75,21 +146,48 @@ async fn main() -> Result<()> {
 
     let args = Args::parse();
 
-    log::info!("Opening Telem Serial Port");
+    let mavlink_connection = match &args.replay_mavlink {
+        Some(path) => {
+            log::info!("Replaying Mavlink stream from {path:?}");
+            format!("file:{}", path.display())
+        }
+        None => args.mavlink_port.clone(),
+    };
+
+    log::info!("Opening Telem connection: {mavlink_connection}");
     let mut telem = Telem::try_new(
-        &args.mavlink_port,
-        args.mavlink_baud,
+        &mavlink_connection,
         args.mavlink_system_id,
         args.mavlink_component_id,
-    )?;
+        args.mavlink_signing()?,
+        args.mavlink_version.into(),
+    )
+    .await?;
+
+    if args.replay_mavlink.is_none() {
+        // Best-effort: some autopilots don't implement MAV_CMD_SET_MESSAGE_INTERVAL, or
+        // just drop a few ACKs. Position tagging is a nicety, not worth bricking the whole
+        // run over, so a failure here just leaves `current_position` unset.
+        if let Err(err) = telem
+            .request_message_interval(GLOBAL_POSITION_INT_MSG_ID, args.position_rate_hz)
+            .await
+        {
+            log::warn!("Failed to request GLOBAL_POSITION_INT stream, continuing without position tagging: {err:?}");
+        }
+    }
 
     log::info!("Creating output directory at {:?}", args.output_directory);
     tokio::fs::create_dir_all(&args.output_directory).await?;
 
     let (mut sensor_a, mut sensor_a_log) = if args.sensor_a_enable {
-        log::info!("Opening Serial Port A: {}:{}", args.sensor_a_port, args.sensor_a_baud);
-
-        let sensor_a = open_serial_port(&args.sensor_a_port, args.sensor_a_baud)?;
+        let sensor_a: std::pin::Pin<Box<dyn futures::Stream<Item = Result<Option<SensorData>>>>> =
+            if let Some(path) = &args.replay_sensor_a {
+                log::info!("Replaying Sensor A log from {path:?}");
+                Box::pin(open_replay_sensor_file(path).await?)
+            } else {
+                log::info!("Opening Serial Port A: {}:{}", args.sensor_a_port, args.sensor_a_baud);
+                Box::pin(open_serial_port(&args.sensor_a_port, args.sensor_a_baud)?)
+            };
 
         let filename_a = args.get_output_file("sensor_a");
         log::info!("Writing log A at {:?}", filename_a);
@@ -113,8 +211,29 @@ async fn main() -> Result<()> {
         (None, None)
     };
 
+    let mut lora = if let Some(port) = &args.lora_port {
+        log::info!("Opening LoRa serial port: {port}:{}", args.lora_baud);
+        let serial = tokio_serial::new(port, args.lora_baud).open_native_async()?;
+        Some(LoraLink::new(serial))
+    } else {
+        None
+    };
+
+    let is_mavlink_replay = args.replay_mavlink.is_some();
+    let is_sensor_a_replay = args.replay_sensor_a.is_some();
+    let mut mavlink_replay_done = false;
+    let mut sensor_a_replay_done = false;
+
     let boot_time = tokio::time::Instant::now();
-    let mut heartbeat_stream = heartbeat_stream(&telem, Duration::from_secs(1));
+    // A replay should reprocess a recorded flight as fast as the files can be read, not pace
+    // itself to the original flight's wall-clock duration, so shrink the heartbeat interval
+    // down to the main loop's own tick instead of waiting a full second between checks.
+    let heartbeat_interval = if is_mavlink_replay || is_sensor_a_replay {
+        Duration::from_millis(1)
+    } else {
+        Duration::from_secs(1)
+    };
+    let mut heartbeat_stream = heartbeat_stream(&telem, heartbeat_interval);
     let mut current_position: Option<DroneLocation> = None;
 
     log::info!("Starting main loop");
@@ -126,8 +245,8 @@ async fn main() -> Result<()> {
         };
 
         // Check if we have receieved any mavlink messages
-        if let Some(Ok((_header, message))) = telem.recv().await {
-            match message {
+        match telem.recv().await {
+            Some(Ok((_header, message))) => match message {
                 MavMessage::HEARTBEAT(_) => (),
                 MavMessage::GLOBAL_POSITION_INT(location) => {
                     current_position = Some(DroneLocation::from(location));
@@ -142,22 +261,38 @@ async fn main() -> Result<()> {
                     }
                 }
                 msg => log::trace!("Recv: {msg:?}"),
+            },
+            Some(Err(err)) => log::warn!("Mavlink recv error: {err:?}"),
+            // `recv()` also yields a spurious `None` right after a decode error, so only
+            // treat this as the replay finishing once the file has actually hit EOF.
+            None if is_mavlink_replay && telem.is_mavlink_replay_eof() => {
+                log::info!("Mavlink replay reached end of file");
+                mavlink_replay_done = true;
             }
+            None => (),
         };
 
         // Check if we need to handle sensor A
         if let (Some(ref mut sensor), Some(ref mut sensor_log)) = (&mut sensor_a, &mut sensor_a_log)
         {
-            if let Some(sensor_result) = sensor.next().await {
-                handle_sensor_data(
-                    &mut telem,
-                    sensor_log,
-                    &current_position,
-                    sensor_result,
-                    boot_time,
-                    "A"
-                )
-                .await?;
+            match sensor.next().await {
+                Some(sensor_result) => {
+                    handle_sensor_data(
+                        &mut telem,
+                        sensor_log,
+                        &current_position,
+                        sensor_result,
+                        boot_time,
+                        "A",
+                        &mut lora,
+                    )
+                    .await?;
+                }
+                None if is_sensor_a_replay => {
+                    log::info!("Sensor A replay reached end of file");
+                    sensor_a_replay_done = true;
+                }
+                None => (),
             }
         }
 
@@ -171,13 +306,25 @@ async fn main() -> Result<()> {
                     &current_position,
                     sensor_result,
                     boot_time,
-                    "B"
+                    "B",
+                    &mut lora,
                 )
                 .await?;
             }
         }
 
+        // If we're replaying from disk, stop once every replayed source has hit EOF
+        if (!is_mavlink_replay || mavlink_replay_done)
+            && (!is_sensor_a_replay || sensor_a_replay_done)
+            && (is_mavlink_replay || is_sensor_a_replay)
+        {
+            log::info!("Replay complete, exiting");
+            break;
+        }
+
         // Wait a little bit, helps to prevent any blocking issues and give the cpu time to do other things
         tokio::time::sleep(Duration::from_millis(1)).await;
     }
+
+    Ok(())
 }
@@ -1,9 +1,10 @@
-use crate::{error::*, mavlink::Telem};
+use crate::{error::*, lora::LoraLink, mavlink::Telem};
 use csv_async::AsyncSerializer;
 use futures::Stream;
 use futures_util::{StreamExt};
 use mavlink::common::GLOBAL_POSITION_INT_DATA;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 use tokio::io::AsyncWriteExt;
 use tokio::time::Instant;
 use tokio::{fs::File, io::AsyncRead};
@@ -149,6 +150,17 @@ pub fn open_serial_port(
     Ok(data)
 }
 
+/// Replays a previously recorded `*_sensor_a.csv`/`*_sensor_b.csv` log back through
+/// [`sensor_data_framed_reader`] as though it were arriving live over a serial port.
+pub async fn open_replay_sensor_file(
+    path: &Path,
+) -> Result<impl Stream<Item = Result<Option<SensorData>>>> {
+    let file = File::open(path).await?;
+    let data = sensor_data_framed_reader(file);
+
+    Ok(data)
+}
+
 pub async fn handle_sensor_data(
     mavlink: &mut Telem,
     csv: &mut AsyncSerializer<File>,
@@ -156,7 +168,7 @@ pub async fn handle_sensor_data(
     sensor_result: Result<Option<SensorData>>,
     boot_time: Instant,
     sensor_name: &str,
-    lora: &mut Option<impl AsyncWriteExt + Unpin>
+    lora: &mut Option<LoraLink<impl AsyncWriteExt + Unpin>>,
 ) -> Result<()> {
     let mut sensor: SensorData = if let Ok(Some(sensor)) = sensor_result {
         sensor
@@ -179,7 +191,7 @@ pub async fn handle_sensor_data(
         writer.serialize(&sensor).await?;
 
         let data = String::from_utf8(writer.into_inner().await.unwrap()).unwrap();
-        lora.write(format!("{sensor_name},{}", data).as_bytes()).await?;
+        lora.send(format!("{sensor_name},{}", data).as_bytes()).await?;
     }
 
     log::debug!("Sensor Data: {sensor:?}");
@@ -27,6 +27,9 @@ pub enum SensorError {
     #[error("mavlink recv error")]
     MavlinkRecvError,
 
+    #[error("mavlink signature verification failed")]
+    MavlinkSignatureError,
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -0,0 +1,196 @@
+use crate::error::*;
+use crc_any::CRCu16;
+use tokio::io::AsyncWriteExt;
+
+/// Marks the start of a framed packet on the LoRa link, letting a receiver resync after
+/// a dropped or truncated transmission.
+pub const LORA_FRAME_START_BYTE: u8 = 0x7e;
+
+/// Number of bytes in a frame besides the payload: start byte, length, sequence, 2-byte CRC.
+const LORA_FRAME_OVERHEAD: usize = 5;
+
+/// Framed packet decoded off a LoRa link, with the sequence number recovered from the
+/// frame so a receiver can report loss via gaps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoraPacket {
+    pub sequence: u8,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoraDecodeError {
+    /// Not enough bytes buffered yet to decode a full frame.
+    Incomplete,
+    /// A full frame was present but its CRC-16 didn't match. Carries the number of bytes
+    /// the caller should skip from the start of `buf` to resync on the next start byte.
+    CrcMismatch(usize),
+}
+
+/// Frames outgoing LoRa payloads with a start byte, length, rolling sequence number and a
+/// CRC-16 so a lossy RF link can detect truncation, corruption or dropped packets.
+pub struct LoraFramer {
+    sequence: u8,
+}
+
+impl Default for LoraFramer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoraFramer {
+    pub fn new() -> Self {
+        Self { sequence: 0 }
+    }
+
+    /// Encodes `payload` as `start_byte | len | seq | payload | crc16`, bumping the
+    /// rolling sequence counter so the receiver can detect gaps.
+    pub fn encode(&mut self, payload: &[u8]) -> Vec<u8> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut frame = Vec::with_capacity(LORA_FRAME_OVERHEAD + payload.len());
+        frame.push(LORA_FRAME_START_BYTE);
+        frame.push(payload.len() as u8);
+        frame.push(sequence);
+        frame.extend_from_slice(payload);
+
+        let mut crc_calc = CRCu16::crc16mcrf4cc();
+        crc_calc.digest(&frame[1..]);
+        frame.extend_from_slice(&crc_calc.get_crc().to_le_bytes());
+
+        frame
+    }
+}
+
+/// Scans `buf` for a `LoraPacket`, returning the packet and the number of bytes it consumed
+/// from the start of `buf` (including any garbage skipped before resyncing on the start byte).
+/// Returns `Err(Incomplete)` if `buf` doesn't yet contain a full frame.
+pub fn decode(buf: &[u8]) -> std::result::Result<(LoraPacket, usize), LoraDecodeError> {
+    let Some(start) = buf.iter().position(|&b| b == LORA_FRAME_START_BYTE) else {
+        return Err(LoraDecodeError::Incomplete);
+    };
+
+    let buf = &buf[start..];
+    let Some(&payload_len) = buf.get(1) else {
+        return Err(LoraDecodeError::Incomplete);
+    };
+    let payload_len = payload_len as usize;
+    let frame_len = LORA_FRAME_OVERHEAD + payload_len;
+
+    if buf.len() < frame_len {
+        return Err(LoraDecodeError::Incomplete);
+    }
+
+    let sequence = buf[2];
+    let payload = &buf[3..3 + payload_len];
+    let crc = u16::from_le_bytes([buf[3 + payload_len], buf[4 + payload_len]]);
+
+    let mut crc_calc = CRCu16::crc16mcrf4cc();
+    crc_calc.digest(&buf[1..3 + payload_len]);
+    if crc_calc.get_crc() != crc {
+        return Err(LoraDecodeError::CrcMismatch(start + 1));
+    }
+
+    Ok((
+        LoraPacket {
+            sequence,
+            payload: payload.to_vec(),
+        },
+        start + frame_len,
+    ))
+}
+
+/// Pairs a [`LoraFramer`] with the writer it sends framed packets over.
+pub struct LoraLink<W> {
+    writer: W,
+    framer: LoraFramer,
+}
+
+impl<W: AsyncWriteExt + Unpin> LoraLink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            framer: LoraFramer::new(),
+        }
+    }
+
+    pub async fn send(&mut self, payload: &[u8]) -> Result<()> {
+        let frame = self.framer.encode(payload);
+        self.writer.write_all(&frame).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_a_single_packet() {
+        let mut framer = LoraFramer::new();
+        let frame = framer.encode(b"A,239.983,28.1712");
+
+        let (packet, consumed) = decode(&frame).unwrap();
+        assert_eq!(consumed, frame.len());
+        assert_eq!(packet.sequence, 0);
+        assert_eq!(packet.payload, b"A,239.983,28.1712");
+    }
+
+    #[test]
+    fn sequence_number_increments_across_packets() {
+        let mut framer = LoraFramer::new();
+        let first = framer.encode(b"one");
+        let second = framer.encode(b"two");
+
+        let (first, _) = decode(&first).unwrap();
+        let (second, _) = decode(&second).unwrap();
+
+        assert_eq!(first.sequence, 0);
+        assert_eq!(second.sequence, 1);
+    }
+
+    #[test]
+    fn resyncs_on_the_start_byte_after_leading_garbage() {
+        let mut framer = LoraFramer::new();
+        let mut buf = vec![0xAA, 0xBB, 0xCC];
+        buf.extend(framer.encode(b"B,1.0"));
+
+        let (packet, consumed) = decode(&buf).unwrap();
+        assert_eq!(consumed, buf.len());
+        assert_eq!(packet.payload, b"B,1.0");
+    }
+
+    #[test]
+    fn rejects_a_corrupted_payload() {
+        let mut framer = LoraFramer::new();
+        let mut frame = framer.encode(b"A,239.983");
+        let last = frame.len() - 3;
+        frame[last] ^= 0xFF;
+
+        assert_eq!(decode(&frame), Err(LoraDecodeError::CrcMismatch(1)));
+    }
+
+    #[test]
+    fn crc_mismatch_reports_offset_to_resync_past_a_bogus_start_byte() {
+        let mut framer = LoraFramer::new();
+        let mut buf = vec![LORA_FRAME_START_BYTE, 0xAA, 0xBB]; // bogus frame start
+        buf.extend(framer.encode(b"B,1.0")); // real frame follows
+
+        let Err(LoraDecodeError::CrcMismatch(skip)) = decode(&buf) else {
+            panic!("expected a CRC mismatch on the bogus frame start");
+        };
+
+        let (packet, consumed) = decode(&buf[skip..]).unwrap();
+        assert_eq!(skip + consumed, buf.len());
+        assert_eq!(packet.payload, b"B,1.0");
+    }
+
+    #[test]
+    fn reports_incomplete_frames() {
+        let mut framer = LoraFramer::new();
+        let frame = framer.encode(b"A,239.983");
+
+        assert_eq!(decode(&frame[..frame.len() - 1]), Err(LoraDecodeError::Incomplete));
+    }
+}
@@ -2,10 +2,85 @@ use crate::error::SensorError;
 use bytes::{Buf, BufMut};
 use crc_any::CRCu16;
 use mavlink::{MavHeader, MavlinkVersion};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio_util::codec::{Decoder, Encoder};
 
+/// `MAVLINK_IFLAG_SIGNED`, set on the incompat flags byte of a v2 header
+/// when a 13-byte signature trailer follows the checksum.
+const MAVLINK_IFLAG_SIGNED: u8 = 0x01;
+
+/// MAVLink signing timestamps count 10us ticks since 00:00 on 1 Jan 2015 UTC.
+const MAVLINK_SIGNING_EPOCH_UNIX_SECS: u64 = 1_420_070_400;
+
+/// Opt-in MAVLink v2 message signing, configured per-link with a shared
+/// secret key and link id. Securing the timestamp in an `AtomicU64` lets it
+/// only ever move forward, even if `encode` is somehow called concurrently.
+pub struct MavSigningConfig {
+    pub link_id: u8,
+    pub secret_key: [u8; 32],
+    last_timestamp: AtomicU64,
+}
+
+impl MavSigningConfig {
+    pub fn new(link_id: u8, secret_key: [u8; 32]) -> Self {
+        Self {
+            link_id,
+            secret_key,
+            last_timestamp: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a signing timestamp strictly greater than the last one handed out.
+    fn next_timestamp(&self) -> u64 {
+        let now = current_signing_timestamp();
+        // `fetch_update` resolves to the *previous* value on success, not the new one,
+        // so the timestamp we hand out has to be captured from inside the closure.
+        let mut assigned = now;
+        let _ = self
+            .last_timestamp
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                assigned = now.max(last + 1);
+                Some(assigned)
+            });
+        assigned
+    }
+}
+
+fn current_signing_timestamp() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let since_signing_epoch = since_epoch
+        .as_secs()
+        .saturating_sub(MAVLINK_SIGNING_EPOCH_UNIX_SECS);
+
+    since_signing_epoch * 100_000 + since_epoch.subsec_micros() as u64 / 10
+}
+
+fn mavlink_signature(secret_key: &[u8; 32], frame: &[u8], link_id: u8, timestamp: u64) -> [u8; 6] {
+    let mut hasher = Sha256::new();
+    hasher.update(secret_key);
+    hasher.update(frame);
+    hasher.update([link_id]);
+    hasher.update(&timestamp.to_le_bytes()[..6]);
+
+    let digest = hasher.finalize();
+    let mut signature = [0u8; 6];
+    signature.copy_from_slice(&digest[..6]);
+    signature
+}
+
 pub struct MavMessageCodec<M> {
+    /// Mavlink protocol version used for outgoing, unsigned frames. Signing always
+    /// produces a v2 frame regardless of this setting, since v1 has no signature trailer.
+    version: MavlinkVersion,
+    signing: Option<MavSigningConfig>,
+    /// Last accepted signing timestamp per `(sysid, compid, link_id)`, used to reject replays.
+    last_timestamps: HashMap<(u8, u8, u8), u64>,
     _phantom: PhantomData<M>,
 }
 
@@ -18,9 +93,24 @@ impl<M: mavlink::Message> Default for MavMessageCodec<M> {
 impl<M: mavlink::Message> MavMessageCodec<M> {
     pub fn new() -> MavMessageCodec<M> {
         MavMessageCodec {
+            version: MavlinkVersion::V2,
+            signing: None,
+            last_timestamps: HashMap::new(),
             _phantom: PhantomData {},
         }
     }
+
+    /// Enables MAVLink v2 signing of outgoing frames and verification of incoming ones.
+    pub fn with_signing(mut self, signing: MavSigningConfig) -> Self {
+        self.signing = Some(signing);
+        self
+    }
+
+    /// Sets the Mavlink protocol version used to encode outgoing, unsigned frames.
+    pub fn with_version(mut self, version: MavlinkVersion) -> Self {
+        self.version = version;
+        self
+    }
 }
 
 impl<M: mavlink::Message> Encoder<(mavlink::MavHeader, M)> for MavMessageCodec<M> {
@@ -32,11 +122,64 @@ impl<M: mavlink::Message> Encoder<(mavlink::MavHeader, M)> for MavMessageCodec<M
         dst: &mut bytes::BytesMut,
     ) -> Result<(), Self::Error> {
         let (header, data) = message;
-        let mut writer = dst.writer();
 
-        mavlink::write_v2_msg(&mut writer, header, &data)
+        let Some(signing) = &self.signing else {
+            let mut writer = dst.writer();
+
+            match self.version {
+                MavlinkVersion::V1 => mavlink::write_v1_msg(&mut writer, header, &data),
+                MavlinkVersion::V2 => mavlink::write_v2_msg(&mut writer, header, &data),
+            }
             .map_err(|_| SensorError::MavlinkSendError)?;
 
+            return Ok(());
+        };
+
+        let mut payload_buf = [0u8; 255];
+        let payload_len = data.ser(MavlinkVersion::V2, &mut payload_buf);
+        let payload = &payload_buf[..payload_len];
+        let msgid = data.message_id();
+        let msgid_bytes = msgid.to_le_bytes();
+
+        let header_buf = [
+            payload.len() as u8,
+            MAVLINK_IFLAG_SIGNED,
+            0, // compat_flags
+            header.sequence,
+            header.system_id,
+            header.component_id,
+            msgid_bytes[0],
+            msgid_bytes[1],
+            msgid_bytes[2],
+        ];
+
+        let mut crc_calc = CRCu16::crc16mcrf4cc();
+        crc_calc.digest(&header_buf);
+        crc_calc.digest(payload);
+        crc_calc.digest(&[M::extra_crc(msgid)]);
+        let crc = crc_calc.get_crc();
+
+        let frame_start = dst.len();
+        dst.put_u8(mavlink::MAV_STX_V2);
+        dst.put_slice(&header_buf);
+        dst.put_slice(payload);
+        dst.put_u16_le(crc);
+
+        let timestamp = signing.next_timestamp();
+        let mut timestamp_buf = [0u8; 6];
+        timestamp_buf.copy_from_slice(&timestamp.to_le_bytes()[..6]);
+
+        let signature = mavlink_signature(
+            &signing.secret_key,
+            &dst[frame_start..],
+            signing.link_id,
+            timestamp,
+        );
+
+        dst.put_u8(signing.link_id);
+        dst.put_slice(&timestamp_buf);
+        dst.put_slice(&signature);
+
         Ok(())
     }
 }
@@ -49,7 +192,66 @@ impl<M: mavlink::Message> Decoder for MavMessageCodec<M> {
         &mut self,
         src: &mut bytes::BytesMut,
     ) -> std::result::Result<Option<Self::Item>, Self::Error> {
-        match src.iter().position(|&byte| byte == mavlink::MAV_STX_V2) {
+        match src
+            .iter()
+            .position(|&byte| byte == mavlink::MAV_STX_V2 || byte == mavlink::MAV_STX)
+        {
+            Some(index) if src[index] == mavlink::MAV_STX => {
+                src.advance(index);
+
+                let payload_len = match src.get(1) {
+                    Some(&len) => len as usize,
+                    None => return Ok(None),
+                };
+                /* v1 header: STX, len, seq, sysid, compid, msgid (6 bytes) + payload + 2-byte crc */
+                let message_len = 6 + payload_len + 2;
+                if src.remaining() < message_len {
+                    return Ok(None);
+                }
+
+                src.advance(1); // skip over STX
+                let payload_len = src.get_u8() as usize;
+                let seq = src.get_u8();
+                let sysid = src.get_u8();
+                let compid = src.get_u8();
+                let msgid = src.get_u8();
+
+                let header_buf = &[payload_len as u8, seq, sysid, compid, msgid];
+                let payload = src.split_to(payload_len);
+                let crc = src.get_u16_le();
+
+                let mut crc_calc = CRCu16::crc16mcrf4cc();
+                crc_calc.digest(&header_buf[..]);
+                crc_calc.digest(&payload[..]);
+                crc_calc.digest(&[M::extra_crc(msgid as u32)]);
+
+                if crc_calc.get_crc() != crc {
+                    /* CRC check failed, skip this message */
+                    return Ok(None);
+                }
+
+                if self.signing.is_some() {
+                    // V1 frames have no signature trailer at all, so once signing is
+                    // configured there is no way for a V1 frame to be authenticated.
+                    log::warn!(
+                        "Rejecting unsigned mavlink v1 frame from sysid {sysid} compid {compid}: signing is required on this link"
+                    );
+                    return Err(SensorError::MavlinkSignatureError);
+                }
+
+                M::parse(MavlinkVersion::V1, msgid as u32, &payload[..])
+                    .map(|msg| {
+                        Some((
+                            MavHeader {
+                                sequence: seq,
+                                system_id: sysid,
+                                component_id: compid,
+                            },
+                            msg,
+                        ))
+                    })
+                    .map_err(|err| err.into())
+            }
             Some(index) => {
                 src.advance(index);
                 let payload_len = match src.get(1) {
@@ -57,7 +259,7 @@ impl<M: mavlink::Message> Decoder for MavMessageCodec<M> {
                     None => return Ok(None),
                 };
                 let has_signature = match src.get(2) {
-                    Some(flags) => flags & 0x01 == 0x01, // MAVLINK_IFLAG_SIGNED
+                    Some(flags) => flags & MAVLINK_IFLAG_SIGNED == MAVLINK_IFLAG_SIGNED,
                     None => return Ok(None),
                 };
                 let mut message_len = 12 + payload_len;
@@ -92,9 +294,17 @@ impl<M: mavlink::Message> Decoder for MavMessageCodec<M> {
                     let msgid: u32 = u32::from_le_bytes(msgid_buf);
                     let payload = src.split_to(payload_len);
                     let crc = src.get_u16_le();
+
+                    let mut signature_trailer = None;
                     if has_signature {
-                        src.advance(13);
+                        let link_id = src.get_u8();
+                        let mut timestamp_buf = [0u8; 6];
+                        src.copy_to_slice(&mut timestamp_buf);
+                        let mut signature = [0u8; 6];
+                        src.copy_to_slice(&mut signature);
+                        signature_trailer = Some((link_id, timestamp_buf, signature));
                     }
+
                     let mut crc_calc = CRCu16::crc16mcrf4cc();
                     crc_calc.digest(&header_buf[..]);
                     crc_calc.digest(&payload[..]);
@@ -102,24 +312,59 @@ impl<M: mavlink::Message> Decoder for MavMessageCodec<M> {
 
                     crc_calc.digest(&[extra_crc]);
                     let recvd_crc = crc_calc.get_crc();
-                    if recvd_crc == crc {
-                        /* hack: we should have a CRC error here */
-                        M::parse(MavlinkVersion::V2, msgid, &payload[..])
-                            .map(|msg| {
-                                Some((
-                                    MavHeader {
-                                        sequence: seq,
-                                        system_id: sysid,
-                                        component_id: compid,
-                                    },
-                                    msg,
-                                ))
-                            })
-                            .map_err(|err| err.into())
-                    } else {
+                    if recvd_crc != crc {
                         /* CRC check failed, skip this message */
-                        Ok(None)
+                        return Ok(None);
+                    }
+
+                    if let Some(signing) = &self.signing {
+                        let Some((link_id, timestamp_buf, signature)) = signature_trailer else {
+                            log::warn!(
+                                "Rejecting unsigned mavlink frame from sysid {sysid} compid {compid}: signing is required on this link"
+                            );
+                            return Err(SensorError::MavlinkSignatureError);
+                        };
+
+                        let mut timestamp_bytes = [0u8; 8];
+                        timestamp_bytes[..6].copy_from_slice(&timestamp_buf);
+                        let timestamp = u64::from_le_bytes(timestamp_bytes);
+
+                        let expected = mavlink_signature(
+                            &signing.secret_key,
+                            &[&[mavlink::MAV_STX_V2][..], header_buf, &payload, &crc.to_le_bytes()]
+                                .concat(),
+                            link_id,
+                            timestamp,
+                        );
+
+                        if expected != signature {
+                            return Err(SensorError::MavlinkSignatureError);
+                        }
+
+                        let key = (sysid, compid, link_id);
+                        let last = self.last_timestamps.get(&key).copied().unwrap_or(0);
+                        if timestamp <= last {
+                            log::warn!(
+                                "Dropping replayed mavlink frame from sysid {sysid} compid {compid} link {link_id}"
+                            );
+                            return Ok(None);
+                        }
+                        self.last_timestamps.insert(key, timestamp);
                     }
+
+                    /* hack: we should have a CRC error here */
+                    M::parse(MavlinkVersion::V2, msgid, &payload[..])
+                        .map(|msg| {
+                            Some((
+                                MavHeader {
+                                    sequence: seq,
+                                    system_id: sysid,
+                                    component_id: compid,
+                                },
+                                msg,
+                            ))
+                        })
+                        .map_err(|err| err.into())
                 } else {
                     Ok(None)
                 }
@@ -128,3 +373,110 @@ impl<M: mavlink::Message> Decoder for MavMessageCodec<M> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use mavlink::common::{MavAutopilot, MavMessage, MavModeFlag, MavState, MavType, HEARTBEAT_DATA};
+
+    fn heartbeat() -> MavMessage {
+        MavMessage::HEARTBEAT(HEARTBEAT_DATA {
+            custom_mode: 0,
+            mavtype: MavType::MAV_TYPE_ONBOARD_CONTROLLER,
+            autopilot: MavAutopilot::MAV_AUTOPILOT_INVALID,
+            base_mode: MavModeFlag::empty(),
+            system_status: MavState::MAV_STATE_STANDBY,
+            mavlink_version: 0x3,
+        })
+    }
+
+    fn header() -> MavHeader {
+        MavHeader {
+            sequence: 1,
+            system_id: 42,
+            component_id: 1,
+        }
+    }
+
+    fn signing_codec() -> MavMessageCodec<MavMessage> {
+        MavMessageCodec::new().with_signing(MavSigningConfig::new(7, [0x42; 32]))
+    }
+
+    #[test]
+    fn signs_and_verifies_a_round_trip() {
+        let mut encoder = signing_codec();
+        let mut decoder = signing_codec();
+
+        let mut buf = BytesMut::new();
+        encoder.encode((header(), heartbeat()), &mut buf).unwrap();
+
+        let (decoded_header, decoded_msg) = decoder
+            .decode(&mut buf)
+            .unwrap()
+            .expect("signed frame should decode");
+
+        assert_eq!(decoded_header.system_id, header().system_id);
+        assert_eq!(decoded_msg, heartbeat());
+    }
+
+    #[test]
+    fn rejects_a_tampered_signature() {
+        let mut encoder = signing_codec();
+        let mut decoder = signing_codec();
+
+        let mut buf = BytesMut::new();
+        encoder.encode((header(), heartbeat()), &mut buf).unwrap();
+        let last = buf.len() - 1;
+        buf[last] ^= 0xFF;
+
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(SensorError::MavlinkSignatureError)
+        ));
+    }
+
+    #[test]
+    fn rejects_an_unsigned_frame_when_signing_is_required() {
+        let mut encoder = MavMessageCodec::<MavMessage>::new();
+        let mut decoder = signing_codec();
+
+        let mut buf = BytesMut::new();
+        encoder.encode((header(), heartbeat()), &mut buf).unwrap();
+
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(SensorError::MavlinkSignatureError)
+        ));
+    }
+
+    #[test]
+    fn rejects_a_v1_frame_when_signing_is_required() {
+        let mut encoder = MavMessageCodec::<MavMessage>::new().with_version(MavlinkVersion::V1);
+        let mut decoder = signing_codec();
+
+        let mut buf = BytesMut::new();
+        encoder.encode((header(), heartbeat()), &mut buf).unwrap();
+        assert_eq!(buf[0], mavlink::MAV_STX);
+
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(SensorError::MavlinkSignatureError)
+        ));
+    }
+
+    #[test]
+    fn drops_a_replayed_timestamp() {
+        let mut encoder = signing_codec();
+        let mut decoder = signing_codec();
+
+        let mut frame = BytesMut::new();
+        encoder.encode((header(), heartbeat()), &mut frame).unwrap();
+
+        let mut first = frame.clone();
+        assert!(decoder.decode(&mut first).unwrap().is_some());
+
+        let mut replayed = frame;
+        assert!(decoder.decode(&mut replayed).unwrap().is_none());
+    }
+}
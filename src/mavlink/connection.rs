@@ -0,0 +1,201 @@
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_serial::SerialPortBuilderExt;
+
+use crate::error::*;
+
+/// Adapts a connectionless `UdpSocket` to `AsyncRead`/`AsyncWrite` so it can be framed the
+/// same way as a serial port or TCP stream.
+///
+/// `udpout` connects to a fixed remote address up front, so reads/writes use the socket's
+/// connected `recv`/`send`. `udpin` binds and accepts datagrams from any sender, replying to
+/// whichever sender most recently wrote, mirroring the upstream mavlink connection semantics.
+pub enum UdpTransport {
+    Connected(UdpSocket),
+    Listening {
+        socket: UdpSocket,
+        remote: Option<SocketAddr>,
+    },
+}
+
+impl AsyncRead for UdpTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UdpTransport::Connected(socket) => socket.poll_recv(cx, buf),
+            UdpTransport::Listening { socket, remote } => {
+                let before = buf.filled().len();
+                match socket.poll_recv_from(cx, buf) {
+                    Poll::Ready(Ok(addr)) => {
+                        *remote = Some(addr);
+                        Poll::Ready(Ok(()))
+                    }
+                    Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+                    Poll::Pending => {
+                        buf.set_filled(before);
+                        Poll::Pending
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for UdpTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UdpTransport::Connected(socket) => socket.poll_send(cx, data),
+            UdpTransport::Listening { socket, remote } => match remote {
+                Some(addr) => socket.poll_send_to(cx, data, *addr),
+                /* no peer has spoken yet, nothing to reply to */
+                None => Poll::Ready(Ok(data.len())),
+            },
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A Mavlink transport opened from a connection string, unified behind `AsyncRead`/`AsyncWrite`
+/// so [`super::MavMessageCodec`] can be framed over any of them identically.
+pub enum MavlinkTransport {
+    Serial(tokio_serial::SerialStream),
+    Tcp(TcpStream),
+    Udp(UdpTransport),
+    /// A recorded byte stream being replayed from disk. Reads drain the file until EOF;
+    /// writes are discarded, since there is nothing on the other end of a replay to reply to.
+    /// `eof` latches once a real 0-byte read is observed, so callers can tell a genuine
+    /// end-of-file apart from the spurious `None` `Framed` yields right after a decode error.
+    File { file: File, eof: bool },
+}
+
+impl MavlinkTransport {
+    /// True once a `file:` replay source has hit a real end-of-file. Always false for the
+    /// live serial/TCP/UDP transports.
+    pub fn is_eof(&self) -> bool {
+        matches!(self, MavlinkTransport::File { eof: true, .. })
+    }
+}
+
+impl AsyncRead for MavlinkTransport {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MavlinkTransport::Serial(s) => Pin::new(s).poll_read(cx, buf),
+            MavlinkTransport::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            MavlinkTransport::Udp(s) => Pin::new(s).poll_read(cx, buf),
+            MavlinkTransport::File { file, eof } => {
+                let before = buf.filled().len();
+                let poll = Pin::new(file).poll_read(cx, buf);
+                if let Poll::Ready(Ok(())) = &poll {
+                    if buf.filled().len() == before {
+                        *eof = true;
+                    }
+                }
+                poll
+            }
+        }
+    }
+}
+
+impl AsyncWrite for MavlinkTransport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MavlinkTransport::Serial(s) => Pin::new(s).poll_write(cx, data),
+            MavlinkTransport::Tcp(s) => Pin::new(s).poll_write(cx, data),
+            MavlinkTransport::Udp(s) => Pin::new(s).poll_write(cx, data),
+            MavlinkTransport::File { .. } => Poll::Ready(Ok(data.len())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MavlinkTransport::Serial(s) => Pin::new(s).poll_flush(cx),
+            MavlinkTransport::Tcp(s) => Pin::new(s).poll_flush(cx),
+            MavlinkTransport::Udp(s) => Pin::new(s).poll_flush(cx),
+            MavlinkTransport::File { .. } => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MavlinkTransport::Serial(s) => Pin::new(s).poll_shutdown(cx),
+            MavlinkTransport::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            MavlinkTransport::Udp(s) => Pin::new(s).poll_shutdown(cx),
+            MavlinkTransport::File { .. } => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/// Opens a Mavlink transport from a connection string, matching the scheme used by the
+/// upstream mavlink crate: `serial:<path>:<baud>`, `udpout:<host>:<port>`,
+/// `udpin:<host>:<port>` or `tcpout:<host>:<port>`. `file:<path>` is our own extension for
+/// replaying a recorded Mavlink byte stream from disk.
+pub async fn open_mavlink_connection(connection_string: &str) -> Result<MavlinkTransport> {
+    let (scheme, address) = connection_string
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("invalid mavlink connection string: {connection_string}"))?;
+
+    match scheme {
+        "serial" => {
+            let (port, baud) = address.rsplit_once(':').ok_or_else(|| {
+                anyhow::anyhow!("serial connection string must be serial:<path>:<baud>")
+            })?;
+            let baud: u32 = baud
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid baud rate: {baud}"))?;
+
+            let stream = tokio_serial::new(port, baud).open_native_async()?;
+            Ok(MavlinkTransport::Serial(stream))
+        }
+        "tcpout" => {
+            let stream = TcpStream::connect(address).await?;
+            Ok(MavlinkTransport::Tcp(stream))
+        }
+        "udpout" => {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(address).await?;
+            Ok(MavlinkTransport::Udp(UdpTransport::Connected(socket)))
+        }
+        "udpin" => {
+            let socket = UdpSocket::bind(address).await?;
+            Ok(MavlinkTransport::Udp(UdpTransport::Listening {
+                socket,
+                remote: None,
+            }))
+        }
+        "file" => {
+            let file = File::open(address).await?;
+            Ok(MavlinkTransport::File { file, eof: false })
+        }
+        scheme => Err(SensorError::Other(anyhow::anyhow!(
+            "unsupported mavlink connection scheme: {scheme}"
+        ))),
+    }
+}
@@ -7,32 +7,47 @@ use futures::sink::SinkExt;
 use futures::stream::{Stream, StreamExt};
 use mavlink::common as MavCommon;
 use mavlink::common::MavMessage;
-use mavlink::MavHeader;
+use mavlink::{MavHeader, MavlinkVersion};
 
 use tokio::time::Instant;
-use tokio_serial::SerialPortBuilderExt;
 
 use tokio_stream::wrappers::IntervalStream;
 use tokio_util::codec::Framed;
 
-use super::MavMessageCodec;
+use super::{open_mavlink_connection, MavMessageCodec, MavSigningConfig, MavlinkTransport};
+
+/// Mavlink message id for `GLOBAL_POSITION_INT`.
+pub const GLOBAL_POSITION_INT_MSG_ID: u32 = 33;
+
+/// How long to wait for a `COMMAND_ACK` before retrying a `SET_MESSAGE_INTERVAL` request.
+const SET_MESSAGE_INTERVAL_TIMEOUT: Duration = Duration::from_secs(1);
+const SET_MESSAGE_INTERVAL_RETRIES: u32 = 5;
 
 pub struct Telem {
     system_id: u8,
     component_id: u8,
-    mavlink: Framed<tokio_serial::SerialStream, MavMessageCodec<MavMessage>>,
+    mavlink: Framed<MavlinkTransport, MavMessageCodec<MavMessage>>,
     sequence: AtomicU8,
 }
 
 impl Telem {
-    pub fn try_new(
-        serial_port: &str,
-        baud_rate: u32,
+    /// `mavlink_port` is a connection string, e.g. `serial:/dev/ttyUSB0:57600`,
+    /// `udpout:192.168.1.10:14550`, `udpin:0.0.0.0:14550` or `tcpout:192.168.1.10:5760`.
+    /// `version` selects the outgoing protocol version for unsigned frames, for
+    /// interoperating with autopilots that only speak Mavlink v1.
+    pub async fn try_new(
+        mavlink_port: &str,
         system_id: u8,
         component_id: u8,
+        signing: Option<MavSigningConfig>,
+        version: MavlinkVersion,
     ) -> Result<Self> {
-        let port = tokio_serial::new(serial_port, baud_rate).open_native_async()?;
-        let mavlink = Framed::new(port, MavMessageCodec::<MavMessage>::new());
+        let transport = open_mavlink_connection(mavlink_port).await?;
+        let mut codec = MavMessageCodec::<MavMessage>::new().with_version(version);
+        if let Some(signing) = signing {
+            codec = codec.with_signing(signing);
+        }
+        let mavlink = Framed::new(transport, codec);
         let sequence = AtomicU8::new(0);
 
         Ok(Self {
@@ -61,6 +76,13 @@ impl Telem {
         self.mavlink.next().await
     }
 
+    /// True once a `file:` replay source backing this connection has hit a real end-of-file.
+    /// `recv()` also yields a spurious `None` the poll right after a decode error, so callers
+    /// driving a replay to completion must check this rather than treating every `None` as EOF.
+    pub fn is_mavlink_replay_eof(&self) -> bool {
+        self.mavlink.get_ref().is_eof()
+    }
+
     pub fn heartbeat_message(&self) -> MavMessage {
         MavMessage::HEARTBEAT(MavCommon::HEARTBEAT_DATA {
             custom_mode: 0,
@@ -72,6 +94,76 @@ impl Telem {
         })
     }
 
+    /// Actively requests that the autopilot stream `message_id` at `rate_hz`, via
+    /// `MAV_CMD_SET_MESSAGE_INTERVAL`, retrying until an accepting `COMMAND_ACK` is received,
+    /// for at most `SET_MESSAGE_INTERVAL_RETRIES` attempts. Returns an error if no attempt is
+    /// accepted, or if `recv()` yields `None` while waiting for the ack. This is used at startup so position tagging doesn't depend on the autopilot's default
+    /// stream configuration.
+    pub async fn request_message_interval(&mut self, message_id: u32, rate_hz: f32) -> Result<()> {
+        let interval_us = if rate_hz > 0.0 {
+            1_000_000.0 / rate_hz
+        } else {
+            -1.0
+        };
+
+        let command = MavCommon::COMMAND_LONG_DATA {
+            param1: message_id as f32,
+            param2: interval_us,
+            param3: 0.0,
+            param4: 0.0,
+            param5: 0.0,
+            param6: 0.0,
+            param7: 0.0,
+            command: MavCommon::MavCmd::MAV_CMD_SET_MESSAGE_INTERVAL,
+            target_system: 0,
+            target_component: 0,
+            confirmation: 0,
+        };
+
+        for attempt in 1..=SET_MESSAGE_INTERVAL_RETRIES {
+            log::info!(
+                "Requesting message {message_id} at {rate_hz}Hz (attempt {attempt}/{SET_MESSAGE_INTERVAL_RETRIES})"
+            );
+            self.send(MavMessage::COMMAND_LONG(command.clone())).await?;
+
+            let ack = tokio::time::timeout(SET_MESSAGE_INTERVAL_TIMEOUT, self.wait_for_command_ack(command.command)).await;
+
+            match ack {
+                Ok(Some(ack)) if ack.result == MavCommon::MavResult::MAV_RESULT_ACCEPTED => {
+                    return Ok(());
+                }
+                Ok(Some(ack)) => {
+                    log::warn!("SET_MESSAGE_INTERVAL rejected: {:?}", ack.result);
+                }
+                Ok(None) => {
+                    log::warn!(
+                        "Mavlink stream yielded no message while waiting for COMMAND_ACK (attempt {attempt}/{SET_MESSAGE_INTERVAL_RETRIES}), giving up"
+                    );
+                    return Err(SensorError::MavlinkRecvError);
+                }
+                Err(_) => log::warn!("Timed out waiting for COMMAND_ACK"),
+            }
+        }
+
+        log::warn!(
+            "Giving up on SET_MESSAGE_INTERVAL for message {message_id} after {SET_MESSAGE_INTERVAL_RETRIES} attempts"
+        );
+        Err(SensorError::MavlinkRecvError)
+    }
+
+    async fn wait_for_command_ack(&mut self, command: MavCommon::MavCmd) -> Option<MavCommon::COMMAND_ACK_DATA> {
+        loop {
+            match self.recv().await? {
+                Ok((_, MavMessage::COMMAND_ACK(ack))) if ack.command == command => return Some(ack),
+                Ok(_) => continue,
+                Err(err) => {
+                    log::warn!("Mavlink recv error while waiting for COMMAND_ACK: {err:?}");
+                    continue;
+                }
+            }
+        }
+    }
+
     pub async fn send_float(&mut self, name: &str, value: f32, boot_time: Instant) -> Result<()> {
         self.send(MavMessage::NAMED_VALUE_FLOAT(
             MavCommon::NAMED_VALUE_FLOAT_DATA {